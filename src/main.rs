@@ -1,19 +1,60 @@
-use regex::Regex;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
 
 //define an enum for expressions
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Expr {
     Number(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
     Variable(String),
+    Function { params: Vec<String>, body: Vec<Stmt> },
+    Call(String, Vec<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Neq(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+}
+
+//runtime values produced by evaluation
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Closure {
+        params: Vec<String>,
+        body: Vec<Stmt>,
+        env: Environment,
+    },
+}
+
+//closures compare unequal; everything else compares structurally
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 //struct for variable declaration
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct VariableDeclaration {
     name: String,
     value: Expr,
@@ -22,172 +63,1138 @@ struct VariableDeclaration {
 //struct for programs state
 #[derive(Debug)]
 struct Program {
-    variables: HashMap<String, i64>,
+    variables: Environment,
     statements: Vec<Stmt>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Stmt {
     Assignment(String, Expr),
     Declaration(VariableDeclaration),
     Expression(Expr),
+    Return(Expr),
 }
 
-// a simple parser for variable declation , assignment and expressions
-fn parse_program(input: &str) -> Program {
-    let mut program = Program {
-        variables: HashMap::new(),
-        statements: Vec::new(),
+//a lexical scope plus a link to the scope that encloses it
+#[derive(Debug)]
+struct Scope {
+    vars: HashMap<String, Value>,
+    parent: Option<Environment>,
+}
+
+//a handle to a scope, shared so closures can capture it
+type Environment = Rc<RefCell<Scope>>;
+
+//a fresh global scope with no parent
+fn new_env() -> Environment {
+    Rc::new(RefCell::new(Scope {
+        vars: HashMap::new(),
+        parent: None,
+    }))
+}
+
+//a nested scope whose lookups fall back to `parent`
+fn child_env(parent: &Environment) -> Environment {
+    Rc::new(RefCell::new(Scope {
+        vars: HashMap::new(),
+        parent: Some(parent.clone()),
+    }))
+}
+
+//look a name up, walking outward through enclosing scopes
+fn env_get(env: &Environment, name: &str) -> Option<Value> {
+    let scope = env.borrow();
+    if let Some(value) = scope.vars.get(name) {
+        return Some(value.clone());
+    }
+    match &scope.parent {
+        Some(parent) => env_get(parent, name),
+        None => None,
+    }
+}
+
+//bind a name in the current scope, shadowing any outer binding
+fn env_define(env: &Environment, name: String, value: Value) {
+    env.borrow_mut().vars.insert(name, value);
+}
+
+//update the nearest existing binding, or define one here if none exists
+fn env_assign(env: &Environment, name: &str, value: Value) {
+    if !try_assign(env, name, &value) {
+        env_define(env, name.to_string(), value);
+    }
+}
+
+fn try_assign(env: &Environment, name: &str, value: &Value) -> bool {
+    let parent = {
+        let mut scope = env.borrow_mut();
+        if scope.vars.contains_key(name) {
+            scope.vars.insert(name.to_string(), value.clone());
+            return true;
+        }
+        scope.parent.clone()
     };
-    let re_stmt = Regex::new(r"(\w+)\s*=\s*(\d+|[\w\s\+\-\*/\(\)])").unwrap();
-    let re_decl = Regex::new(r"var\s+(\w+)\s*=\s*(\d+)").unwrap();
+    match parent {
+        Some(parent) => try_assign(&parent, name, value),
+        None => false,
+    }
+}
 
-    for line in input.lines() {
-        if let Some(caps) = re_decl.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str().to_string();
-            let value = caps.get(2).unwrap().as_str().parse().unwrap();
-            program.variables.insert(var_name.clone(), value);
-            program
-                .statements
-                .push(Stmt::Declaration(VariableDeclaration {
-                    name: var_name,
-                    value: Expr::Number(value),
-                }));
-        } else if let Some(caps) = re_stmt.captures(line) {
-            let var_name = caps.get(1).unwrap().as_str().to_string();
-            let value_exp = parse_expression(caps.get(2).unwrap().as_str());
-            program
-                .statements
-                .push(Stmt::Assignment(var_name, value_exp));
-        } else {
-            let exp = parse_expression(line);
-            program.statements.push(Stmt::Expression(exp));
+//errors raised while turning source text into an ast
+#[derive(Debug)]
+enum ParseError {
+    MismatchedParentheses,
+    InvalidExpression,
+}
+
+//errors raised while walking the ast
+#[derive(Debug)]
+enum EvalError {
+    TypeError,
+    UndefinedVariable(String),
+    DivisionByZero,
+    InvalidExpression,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MismatchedParentheses => write!(f, "Mismatched parentheses"),
+            ParseError::InvalidExpression => write!(f, "Invalid expression"),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::TypeError => write!(f, "Type error"),
+            EvalError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            EvalError::DivisionByZero => write!(f, "Division by zero"),
+            EvalError::InvalidExpression => write!(f, "Invalid expression"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Closure { .. } => write!(f, "<closure>"),
+        }
+    }
+}
+
+//keywords the language recognises
+#[derive(Debug, Clone, PartialEq)]
+enum Keyword {
+    Var,
+    Function,
+    Return,
+}
+
+//tokens produced by the lexer
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Eq,
+    Neq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semicolon,
+    Assign,
+    Keyword(Keyword),
+    Eof,
+}
+
+//turn a line of source text into a flat token stream
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            c if c.is_ascii_digit() => {
+                let mut num = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() {
+                        num.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // a trailing `.digits` makes it a float
+                if chars.peek() == Some(&'.') {
+                    num.push('.');
+                    chars.next();
+                    while let Some(&d) = chars.peek() {
+                        if d.is_ascii_digit() {
+                            num.push(d);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    let value = num.parse().map_err(|_| ParseError::InvalidExpression)?;
+                    tokens.push(Token::Float(value));
+                } else {
+                    let value = num.parse().map_err(|_| ParseError::InvalidExpression)?;
+                    tokens.push(Token::Int(value));
+                }
+            }
+            '"' => {
+                chars.next(); // opening quote
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err(ParseError::InvalidExpression),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_alphanumeric() || d == '_' {
+                        ident.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match ident.as_str() {
+                    "var" => tokens.push(Token::Keyword(Keyword::Var)),
+                    "function" => tokens.push(Token::Keyword(Keyword::Function)),
+                    "return" => tokens.push(Token::Keyword(Keyword::Return)),
+                    _ => tokens.push(Token::Ident(ident)),
+                }
+            }
+            '+' => {
+                chars.next();
+                tokens.push(Token::Plus);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Minus);
+            }
+            '*' => {
+                chars.next();
+                tokens.push(Token::Star);
+            }
+            '/' => {
+                chars.next();
+                tokens.push(Token::Slash);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '{' => {
+                chars.next();
+                tokens.push(Token::LBrace);
+            }
+            '}' => {
+                chars.next();
+                tokens.push(Token::RBrace);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            ';' => {
+                chars.next();
+                tokens.push(Token::Semicolon);
+            }
+            '=' => {
+                chars.next();
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::Eq);
+                } else {
+                    tokens.push(Token::Assign);
+                }
+            }
+            '!' => {
+                chars.next();
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::Neq);
+                } else {
+                    return Err(ParseError::InvalidExpression);
+                }
+            }
+            '<' => {
+                chars.next();
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::Le);
+                } else {
+                    tokens.push(Token::Lt);
+                }
+            }
+            '>' => {
+                chars.next();
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::Ge);
+                } else {
+                    tokens.push(Token::Gt);
+                }
+            }
+            _ => return Err(ParseError::InvalidExpression),
         }
     }
-    program
+
+    tokens.push(Token::Eof);
+    Ok(tokens)
 }
 
-//a simple parser that only understands simple arithmetics
+//a precedence-climbing (Pratt) parser over the token stream
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.pos).unwrap_or(&Token::Eof)
+    }
 
-fn parse_expression(input: &str) -> Expr {
-    let re = Regex::new(r"(\d+|\+|\-|\*|\/|\(|\))").unwrap();
-    let tokens: Vec<&str> = re.find_iter(input).map(|mat| mat.as_str()).collect();
+    fn peek_at(&self, offset: usize) -> &Token {
+        self.tokens.get(self.pos + offset).unwrap_or(&Token::Eof)
+    }
 
-    let mut output_queue: Vec<Expr> = Vec::new();
-    let mut operator_stack: Vec<&str> = Vec::new();
+    fn advance(&mut self) -> Token {
+        let token = self.tokens.get(self.pos).cloned().unwrap_or(Token::Eof);
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.advance() == token {
+            Ok(())
+        } else {
+            Err(ParseError::InvalidExpression)
+        }
+    }
 
-    for token in tokens {
+    //left/right binding power for infix operators; right = left + 1 keeps
+    //operators left-associative, and comparisons bind looser than + - * /
+    fn infix_bp(token: &Token) -> Option<(u8, u8)> {
         match token {
-            "+" | "-" | "*" | "/" => {
-                while !operator_stack.is_empty()
-                    && operator_stack.last().unwrap() != &"("
-                    && precedence(operator_stack.last().unwrap()) >= precedence(token)
-                {
-                    let op = operator_stack.pop().unwrap();
-                    apply_op(op, &mut output_queue);
-                }
-                operator_stack.push(token);
-            }
-            "(" => {
-                operator_stack.push(token);
-            }
-            ")" => {
-                while !operator_stack.is_empty() && operator_stack.last().unwrap() != &"(" {
-                    let op = operator_stack.pop().unwrap();
-                    apply_op(op, &mut output_queue);
-                }
-                if !operator_stack.is_empty() && operator_stack.last().unwrap() == &"(" {
-                    operator_stack.pop();
+            Token::Eq | Token::Neq | Token::Lt | Token::Gt | Token::Le | Token::Ge => Some((1, 2)),
+            Token::Plus | Token::Minus => Some((3, 4)),
+            Token::Star | Token::Slash => Some((5, 6)),
+            _ => None,
+        }
+    }
+
+    //parse a function literal once the `function` keyword has been seen
+    fn parse_function(&mut self) -> Result<Expr, ParseError> {
+        self.expect(Token::LParen)?;
+        let mut params = Vec::new();
+        if !matches!(self.peek(), Token::RParen) {
+            loop {
+                match self.advance() {
+                    Token::Ident(p) => params.push(p),
+                    _ => return Err(ParseError::InvalidExpression),
+                }
+                match self.advance() {
+                    Token::Comma => continue,
+                    Token::RParen => break,
+                    _ => return Err(ParseError::InvalidExpression),
+                }
+            }
+        } else {
+            self.advance(); // )
+        }
+
+        self.expect(Token::LBrace)?;
+        let mut body = Vec::new();
+        while !matches!(self.peek(), Token::RBrace) {
+            if matches!(self.peek(), Token::Eof) {
+                return Err(ParseError::InvalidExpression);
+            }
+            body.push(parse_stmt(self)?);
+            if matches!(self.peek(), Token::Semicolon) {
+                self.advance();
+            }
+        }
+        self.advance(); // }
+        Ok(Expr::Function { params, body })
+    }
+
+    //parse a call argument list once the opening paren has been seen
+    fn parse_call(&mut self, name: String) -> Result<Expr, ParseError> {
+        self.advance(); // (
+        let mut args = Vec::new();
+        if !matches!(self.peek(), Token::RParen) {
+            loop {
+                args.push(self.parse_expr(0)?);
+                match self.advance() {
+                    Token::Comma => continue,
+                    Token::RParen => break,
+                    _ => return Err(ParseError::InvalidExpression),
+                }
+            }
+        } else {
+            self.advance(); // )
+        }
+        Ok(Expr::Call(name, args))
+    }
+
+    fn parse_expr(&mut self, min_bp: u8) -> Result<Expr, ParseError> {
+        //prefix position: an atom, a call, a group, or unary minus
+        let mut lhs = match self.advance() {
+            Token::Int(n) => Expr::Number(n),
+            Token::Float(x) => Expr::Float(x),
+            Token::Str(s) => Expr::Str(s),
+            Token::Keyword(Keyword::Function) => self.parse_function()?,
+            Token::Ident(name) => {
+                if matches!(self.peek(), Token::LParen) {
+                    self.parse_call(name)?
                 } else {
-                    panic!("Mismatched parentheses");
+                    match name.as_str() {
+                        "true" => Expr::Bool(true),
+                        "false" => Expr::Bool(false),
+                        _ => Expr::Variable(name),
+                    }
                 }
             }
-            num if num.parse::<i64>().is_ok() => {
-                output_queue.push(Expr::Number(num.parse().unwrap()));
+            Token::LParen => {
+                let inner = self.parse_expr(0)?;
+                match self.advance() {
+                    Token::RParen => inner,
+                    _ => return Err(ParseError::MismatchedParentheses),
+                }
             }
-            var if var.trim().parse::<i64>().is_ok() => {
-                output_queue.push(Expr::Number(var.trim().parse().unwrap()));
+            Token::Minus => {
+                // unary minus binds tighter than any infix operator
+                let rhs = self.parse_expr(7)?;
+                Expr::Sub(Box::new(Expr::Number(0)), Box::new(rhs))
             }
-            var => {
-                output_queue.push(Expr::Variable(var.to_string()));
+            _ => return Err(ParseError::InvalidExpression),
+        };
+
+        //infix loop: keep folding while the next operator binds tight enough
+        while let Some((left_bp, right_bp)) = Self::infix_bp(self.peek()) {
+            if left_bp < min_bp {
+                break;
             }
+            let op = self.advance();
+            let rhs = self.parse_expr(right_bp)?;
+            lhs = build_binary(&op, lhs, rhs);
         }
+
+        Ok(lhs)
+    }
+}
+
+fn build_binary(op: &Token, left: Expr, right: Expr) -> Expr {
+    let (left, right) = (Box::new(left), Box::new(right));
+    match op {
+        Token::Plus => Expr::Add(left, right),
+        Token::Minus => Expr::Sub(left, right),
+        Token::Star => Expr::Mul(left, right),
+        Token::Slash => Expr::Div(left, right),
+        Token::Eq => Expr::Eq(left, right),
+        Token::Neq => Expr::Neq(left, right),
+        Token::Lt => Expr::Lt(left, right),
+        Token::Gt => Expr::Gt(left, right),
+        Token::Le => Expr::Le(left, right),
+        Token::Ge => Expr::Ge(left, right),
+        // only infix operators ever reach here
+        _ => unreachable!("non-operator token in build_binary"),
     }
+}
+
+//classify a statement from the token stream: declaration, assignment,
+//return or bare expression
+fn parse_stmt(parser: &mut Parser) -> Result<Stmt, ParseError> {
+    match parser.peek() {
+        Token::Keyword(Keyword::Var) => {
+            parser.advance(); // var
+            let name = match parser.advance() {
+                Token::Ident(name) => name,
+                _ => return Err(ParseError::InvalidExpression),
+            };
+            parser.expect(Token::Assign)?;
+            let value = parser.parse_expr(0)?;
+            Ok(Stmt::Declaration(VariableDeclaration { name, value }))
+        }
+        Token::Keyword(Keyword::Return) => {
+            parser.advance(); // return
+            Ok(Stmt::Return(parser.parse_expr(0)?))
+        }
+        Token::Ident(_) if matches!(parser.peek_at(1), Token::Assign) => {
+            let name = match parser.advance() {
+                Token::Ident(name) => name,
+                _ => unreachable!(),
+            };
+            parser.advance(); // =
+            Ok(Stmt::Assignment(name, parser.parse_expr(0)?))
+        }
+        _ => Ok(Stmt::Expression(parser.parse_expr(0)?)),
+    }
+}
+
+//parse every statement in a balanced chunk of source; brace-enclosed bodies
+//already spanning several lines arrive here as one token stream
+fn parse_chunk(source: &str, out: &mut Vec<Stmt>) -> Result<(), ParseError> {
+    let tokens = tokenize(source)?;
+    if matches!(tokens.first(), Some(Token::Eof)) {
+        return Ok(());
+    }
+
+    let mut parser = Parser::new(tokens);
+    while !matches!(parser.peek(), Token::Eof) {
+        out.push(parse_stmt(&mut parser)?);
+        if matches!(parser.peek(), Token::Semicolon) {
+            parser.advance();
+        }
+    }
+    Ok(())
+}
+
+//net `{`/`}` nesting introduced by a line, counted over tokens so braces
+//inside string literals do not confuse the accumulator
+fn brace_delta(line: &str) -> Result<i32, ParseError> {
+    let mut delta = 0;
+    for token in tokenize(line)? {
+        match token {
+            Token::LBrace => delta += 1,
+            Token::RBrace => delta -= 1,
+            _ => {}
+        }
+    }
+    Ok(delta)
+}
+
+// a simple parser for variable declation , assignment and expressions
+fn parse_program(input: &str) -> Result<Program, ParseError> {
+    let mut program = Program {
+        variables: new_env(),
+        statements: Vec::new(),
+    };
 
-    while !operator_stack.is_empty() {
-        let op = operator_stack.pop().unwrap();
-        if op == "(" {
-            panic!("Mismatched parentheses");
+    //accumulate lines until their braces balance, so a multi-line function
+    //body is handed to `parse_chunk` as a single statement
+    let mut buffer = String::new();
+    let mut depth = 0;
+    for line in input.lines() {
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(line);
+        depth += brace_delta(line)?;
+        if depth <= 0 {
+            parse_chunk(&buffer, &mut program.statements)?;
+            buffer.clear();
+            depth = 0;
         }
-        apply_op(op, &mut output_queue);
     }
+    if !buffer.trim().is_empty() {
+        parse_chunk(&buffer, &mut program.statements)?;
+    }
+    Ok(program)
+}
 
-    if output_queue.len() != 1 {
-        panic!("Invalid expression");
+//numeric view of a value for mixed arithmetic: ints widen to f64, other
+//values have none
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
     }
-    output_queue.pop().unwrap()
 }
 
-fn apply_op(op: &str, stack: &mut Vec<Expr>) {
-    if stack.len() < 2 {
-        panic!("Invalid expression");
+fn eval_arith(op: &str, left: Value, right: Value) -> Result<Value, EvalError> {
+    match (&left, &right) {
+        (Value::Int(a), Value::Int(b)) => Ok(match op {
+            "+" => Value::Int(a + b),
+            "-" => Value::Int(a - b),
+            "*" => Value::Int(a * b),
+            "/" => {
+                if *b == 0 {
+                    return Err(EvalError::DivisionByZero);
+                }
+                Value::Int(a / b)
+            }
+            _ => return Err(EvalError::InvalidExpression),
+        }),
+        (Value::Str(a), Value::Str(b)) if op == "+" => Ok(Value::Str(format!("{}{}", a, b))),
+        _ => {
+            let (a, b) = (
+                as_float(&left).ok_or(EvalError::TypeError)?,
+                as_float(&right).ok_or(EvalError::TypeError)?,
+            );
+            Ok(match op {
+                "+" => Value::Float(a + b),
+                "-" => Value::Float(a - b),
+                "*" => Value::Float(a * b),
+                "/" => {
+                    if b == 0.0 {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    Value::Float(a / b)
+                }
+                _ => return Err(EvalError::InvalidExpression),
+            })
+        }
     }
-    let right = stack.pop().unwrap();
-    let left = stack.pop().unwrap();
+}
+
+//ordering comparisons are numeric or lexical; equality works on like types
+fn eval_cmp(op: &str, left: Value, right: Value) -> Result<Value, EvalError> {
+    let ordering = match (&left, &right) {
+        (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+        _ => {
+            let (a, b) = (
+                as_float(&left).ok_or(EvalError::TypeError)?,
+                as_float(&right).ok_or(EvalError::TypeError)?,
+            );
+            a.partial_cmp(&b)
+        }
+    };
+    let ordering = ordering.ok_or(EvalError::TypeError)?;
+    use std::cmp::Ordering;
     let result = match op {
-        "+" => Expr::Add(Box::new(left), Box::new(right)),
-        "-" => Expr::Sub(Box::new(left), Box::new(right)),
-        "*" => Expr::Mul(Box::new(left), Box::new(right)),
-        "/" => Expr::Div(Box::new(left), Box::new(right)),
-        _ => panic!("Unknown operator: {}", op),
+        "<" => ordering == Ordering::Less,
+        ">" => ordering == Ordering::Greater,
+        "<=" => ordering != Ordering::Greater,
+        ">=" => ordering != Ordering::Less,
+        _ => return Err(EvalError::InvalidExpression),
     };
-    stack.push(result);
+    Ok(Value::Bool(result))
 }
 
-fn precedence(op: &str) -> usize {
-    match op {
-        "-" | "+" => 1,
-        "/" | "*" => 2,
-        _ => 0,
+fn evaluate_expr(expr: &Expr, env: &Environment) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Number(n) => Ok(Value::Int(*n)),
+        Expr::Float(x) => Ok(Value::Float(*x)),
+        Expr::Bool(b) => Ok(Value::Bool(*b)),
+        Expr::Str(s) => Ok(Value::Str(s.clone())),
+        Expr::Variable(name) => {
+            env_get(env, name).ok_or_else(|| EvalError::UndefinedVariable(name.clone()))
+        }
+        Expr::Function { params, body } => Ok(Value::Closure {
+            params: params.clone(),
+            body: body.clone(),
+            env: env.clone(),
+        }),
+        Expr::Call(name, args) => {
+            let callee =
+                env_get(env, name).ok_or_else(|| EvalError::UndefinedVariable(name.clone()))?;
+            let (params, body, captured) = match callee {
+                Value::Closure {
+                    params,
+                    body,
+                    env: captured,
+                } => (params, body, captured),
+                _ => return Err(EvalError::TypeError),
+            };
+            if params.len() != args.len() {
+                return Err(EvalError::TypeError);
+            }
+            // arguments evaluate in the caller's scope, then bind in a fresh
+            // scope whose parent is the closure's captured environment
+            let call_env = child_env(&captured);
+            for (param, arg) in params.iter().zip(args) {
+                let value = evaluate_expr(arg, env)?;
+                env_define(&call_env, param.clone(), value);
+            }
+            match eval_body(&body, &call_env)? {
+                Flow::Return(value) | Flow::Normal(value) => Ok(value),
+            }
+        }
+        Expr::Add(left, right) => {
+            eval_arith("+", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Sub(left, right) => {
+            eval_arith("-", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Mul(left, right) => {
+            eval_arith("*", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Div(left, right) => {
+            eval_arith("/", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Eq(left, right) => Ok(Value::Bool(
+            evaluate_expr(left, env)? == evaluate_expr(right, env)?,
+        )),
+        Expr::Neq(left, right) => Ok(Value::Bool(
+            evaluate_expr(left, env)? != evaluate_expr(right, env)?,
+        )),
+        Expr::Lt(left, right) => {
+            eval_cmp("<", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Gt(left, right) => {
+            eval_cmp(">", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Le(left, right) => {
+            eval_cmp("<=", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
+        Expr::Ge(left, right) => {
+            eval_cmp(">=", evaluate_expr(left, env)?, evaluate_expr(right, env)?)
+        }
     }
 }
 
-fn evaluate_expr(expr: &Expr, vars: &HashMap<String, i64>) -> i64 {
+//the outcome of running a statement: either fall through or unwind a `return`
+enum Flow {
+    Normal(Value),
+    Return(Value),
+}
+
+impl Flow {
+    fn into_value(self) -> Value {
+        match self {
+            Flow::Normal(value) | Flow::Return(value) => value,
+        }
+    }
+}
+
+fn eval_stmt(stmt: &Stmt, env: &Environment) -> Result<Flow, EvalError> {
+    match stmt {
+        Stmt::Declaration(decl) => {
+            let value = evaluate_expr(&decl.value, env)?;
+            env_define(env, decl.name.clone(), value.clone());
+            Ok(Flow::Normal(value))
+        }
+        Stmt::Assignment(name, expr) => {
+            let value = evaluate_expr(expr, env)?;
+            env_assign(env, name, value.clone());
+            Ok(Flow::Normal(value))
+        }
+        Stmt::Expression(expr) => Ok(Flow::Normal(evaluate_expr(expr, env)?)),
+        Stmt::Return(expr) => Ok(Flow::Return(evaluate_expr(expr, env)?)),
+    }
+}
+
+//run a body of statements, propagating an early `return` to the caller
+fn eval_body(body: &[Stmt], env: &Environment) -> Result<Flow, EvalError> {
+    let mut last = Value::Int(0);
+    for stmt in body {
+        match eval_stmt(stmt, env)? {
+            Flow::Return(value) => return Ok(Flow::Return(value)),
+            Flow::Normal(value) => last = value,
+        }
+    }
+    Ok(Flow::Normal(last))
+}
+
+//a single stack-machine instruction
+#[derive(Debug, Clone)]
+enum Op {
+    PushConst(usize),
+    LoadVar(usize),
+    StoreVar(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Return,
+}
+
+//a compiled unit: the instruction stream plus its constant and variable pools
+#[derive(Debug, Default)]
+struct Chunk {
+    code: Vec<Op>,
+    constants: Vec<Value>,
+    names: Vec<String>,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk::default()
+    }
+
+    fn add_const(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    //map a variable name to a stable slot index, reusing it on repeat
+    fn intern(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.code.push(op);
+    }
+}
+
+//lower an expression to postfix bytecode: compile both operands, then the
+//operator, matching the order `Vm::binary` pops them (right, then left)
+fn compile_expr(expr: &Expr, chunk: &mut Chunk) -> Result<(), EvalError> {
     match expr {
-        Expr::Number(n) => *n,
-        Expr::Variable(name) => *vars.get(name).expect("Undefined Variable;"),
-        Expr::Add(left, right) => evaluate_expr(left, vars) + evaluate_expr(right, vars),
-        Expr::Sub(left, right) => evaluate_expr(left, vars) - evaluate_expr(right, vars),
-        Expr::Mul(left, right) => evaluate_expr(left, vars) * evaluate_expr(right, vars),
-        Expr::Div(left, right) => evaluate_expr(left, vars) / evaluate_expr(right, vars),
+        Expr::Number(n) => {
+            let c = chunk.add_const(Value::Int(*n));
+            chunk.emit(Op::PushConst(c));
+        }
+        Expr::Float(x) => {
+            let c = chunk.add_const(Value::Float(*x));
+            chunk.emit(Op::PushConst(c));
+        }
+        Expr::Bool(b) => {
+            let c = chunk.add_const(Value::Bool(*b));
+            chunk.emit(Op::PushConst(c));
+        }
+        Expr::Str(s) => {
+            let c = chunk.add_const(Value::Str(s.clone()));
+            chunk.emit(Op::PushConst(c));
+        }
+        Expr::Variable(name) => {
+            let slot = chunk.intern(name);
+            chunk.emit(Op::LoadVar(slot));
+        }
+        Expr::Add(left, right) => {
+            compile_expr(left, chunk)?;
+            compile_expr(right, chunk)?;
+            chunk.emit(Op::Add);
+        }
+        Expr::Sub(left, right) => {
+            compile_expr(left, chunk)?;
+            compile_expr(right, chunk)?;
+            chunk.emit(Op::Sub);
+        }
+        Expr::Mul(left, right) => {
+            compile_expr(left, chunk)?;
+            compile_expr(right, chunk)?;
+            chunk.emit(Op::Mul);
+        }
+        Expr::Div(left, right) => {
+            compile_expr(left, chunk)?;
+            compile_expr(right, chunk)?;
+            chunk.emit(Op::Div);
+        }
+        // comparisons, closures and calls have no bytecode yet
+        _ => return Err(EvalError::InvalidExpression),
     }
+    Ok(())
 }
 
-fn main() {
-    let js_code = r#"3 + (4 * 2) / (1 - 5)"#;
-    let mut program = parse_program(js_code);
+//lower a statement; declarations and assignments copy their result into a slot
+fn compile_stmt(stmt: &Stmt, chunk: &mut Chunk) -> Result<(), EvalError> {
+    match stmt {
+        Stmt::Declaration(decl) => {
+            compile_expr(&decl.value, chunk)?;
+            let slot = chunk.intern(&decl.name);
+            chunk.emit(Op::StoreVar(slot));
+        }
+        Stmt::Assignment(name, expr) => {
+            compile_expr(expr, chunk)?;
+            let slot = chunk.intern(name);
+            chunk.emit(Op::StoreVar(slot));
+        }
+        Stmt::Expression(expr) => compile_expr(expr, chunk)?,
+        Stmt::Return(expr) => {
+            compile_expr(expr, chunk)?;
+            chunk.emit(Op::Return);
+        }
+    }
+    Ok(())
+}
+
+//a small stack machine that executes a compiled chunk
+struct Vm {
+    stack: Vec<Value>,
+    slots: Vec<Option<Value>>,
+}
 
-    println!("Parsed Program: {:?}", program);
+impl Vm {
+    fn new(slot_count: usize) -> Self {
+        Vm {
+            stack: Vec::new(),
+            slots: vec![None; slot_count],
+        }
+    }
 
+    fn binary(&mut self, op: &str) -> Result<(), EvalError> {
+        let right = self.stack.pop().ok_or(EvalError::InvalidExpression)?;
+        let left = self.stack.pop().ok_or(EvalError::InvalidExpression)?;
+        self.stack.push(eval_arith(op, left, right)?);
+        Ok(())
+    }
+
+    fn run(&mut self, chunk: &Chunk) -> Result<Value, EvalError> {
+        for op in &chunk.code {
+            match op {
+                Op::PushConst(index) => self.stack.push(chunk.constants[*index].clone()),
+                Op::LoadVar(index) => {
+                    let value = self.slots[*index]
+                        .clone()
+                        .ok_or_else(|| EvalError::UndefinedVariable(chunk.names[*index].clone()))?;
+                    self.stack.push(value);
+                }
+                Op::StoreVar(index) => {
+                    //store leaves the value on the stack so a declaration or
+                    //assignment as the final statement still yields a result
+                    let value = self.stack.last().ok_or(EvalError::InvalidExpression)?.clone();
+                    self.slots[*index] = Some(value);
+                }
+                Op::Add => self.binary("+")?,
+                Op::Sub => self.binary("-")?,
+                Op::Mul => self.binary("*")?,
+                Op::Div => self.binary("/")?,
+                Op::Return => return self.stack.pop().ok_or(EvalError::InvalidExpression),
+            }
+        }
+        self.stack.pop().ok_or(EvalError::InvalidExpression)
+    }
+}
+
+//run a whole source file and print what each statement produces
+fn run_file(source: &str) {
+    let program = match parse_program(source) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            return;
+        }
+    };
+
+    let env = program.variables;
     for stmt in &program.statements {
-        match stmt {
-            Stmt::Assignment(var, expr) => {
-                let result = evaluate_expr(expr, &program.variables);
-                program.variables.insert(var.clone(), result);
-                println!("Assignment: {} = {}", var, result);
+        match eval_stmt(stmt, &env) {
+            Ok(flow) => {
+                let value = flow.into_value();
+                match stmt {
+                    Stmt::Assignment(var, _) => println!("Assignment: {} = {}", var, value),
+                    Stmt::Declaration(decl) => println!("Declaration: {} = {}", decl.name, value),
+                    Stmt::Expression(_) => println!("Expression: {}", value),
+                    Stmt::Return(_) => println!("Return: {}", value),
+                }
+            }
+            Err(e) => eprintln!("Eval error: {}", e),
+        }
+    }
+}
+
+//interactive calculator shell; variables persist across prompts
+fn repl() {
+    let stdin = io::stdin();
+    let env = new_env();
+
+    loop {
+        print!("> ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break, // end of input
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
             }
-            Stmt::Declaration(decl) => {
-                println!(
-                    "Declaration: {} ={}",
-                    decl.name,
-                    program.variables.get(&decl.name).unwrap()
-                );
-                let _ = decl.value;
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let program = match parse_program(line) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("Parse error: {}", e);
+                continue;
             }
-            Stmt::Expression(expr) => {
-                let result = evaluate_expr(expr, &program.variables);
-                println!("Expression: {}", result);
+        };
+
+        //evaluate each statement against the long-lived environment,
+        //remembering the value of the last expression to echo it back
+        let mut last = None;
+        for stmt in &program.statements {
+            match eval_stmt(stmt, &env) {
+                Ok(flow) => {
+                    if let Stmt::Expression(_) = stmt {
+                        last = Some(flow.into_value());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Eval error: {}", e);
+                    last = None;
+                    break;
+                }
             }
         }
+
+        if let Some(value) = last {
+            println!("{}", value);
+        }
+    }
+}
+
+//compile a source file to bytecode, run it on the vm and print the result
+fn run_compiled(source: &str) {
+    let program = match parse_program(source) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            return;
+        }
+    };
+
+    let mut chunk = Chunk::new();
+    for stmt in &program.statements {
+        if let Err(e) = compile_stmt(stmt, &mut chunk) {
+            eprintln!("Compile error: {}", e);
+            return;
+        }
+    }
+    chunk.emit(Op::Return);
+
+    let mut vm = Vm::new(chunk.names.len());
+    match vm.run(&chunk) {
+        Ok(value) => println!("{}", value),
+        Err(e) => eprintln!("Runtime error: {}", e),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("compile") => match args.get(2) {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(source) => run_compiled(&source),
+                Err(e) => eprintln!("Could not read {}: {}", path, e),
+            },
+            None => eprintln!("Usage: compile <file>"),
+        },
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(source) => run_file(&source),
+            Err(e) => eprintln!("Could not read {}: {}", path, e),
+        },
+        None => repl(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //parse and tree-walk a source snippet, returning the last value produced
+    fn eval_source(source: &str) -> Value {
+        let program = parse_program(source).expect("parse");
+        let env = program.variables;
+        let mut last = Value::Int(0);
+        for stmt in &program.statements {
+            last = eval_stmt(stmt, &env).expect("eval").into_value();
+        }
+        last
+    }
+
+    //compile the snippet to bytecode and run it on the vm
+    fn compile_source(source: &str) -> Value {
+        let program = parse_program(source).expect("parse");
+        let mut chunk = Chunk::new();
+        for stmt in &program.statements {
+            compile_stmt(stmt, &mut chunk).expect("compile");
+        }
+        chunk.emit(Op::Return);
+        let mut vm = Vm::new(chunk.names.len());
+        vm.run(&chunk).expect("run")
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(eval_source("2 + 3 * 4"), Value::Int(14));
+    }
+
+    #[test]
+    fn subtraction_is_left_associative() {
+        assert_eq!(eval_source("10 - 3 - 2"), Value::Int(5));
+    }
+
+    #[test]
+    fn unary_minus_is_a_prefix_operator() {
+        assert_eq!(eval_source("-5 + 2"), Value::Int(-3));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(eval_source("(2 + 3) * 4"), Value::Int(20));
+    }
+
+    #[test]
+    fn declared_variables_persist_across_statements() {
+        assert_eq!(eval_source("var x = 5\nx + 2"), Value::Int(7));
+    }
+
+    #[test]
+    fn strings_concatenate_with_plus() {
+        assert_eq!(
+            eval_source("\"foo\" + \"bar\""),
+            Value::Str("foobar".to_string())
+        );
+    }
+
+    #[test]
+    fn comparisons_produce_booleans() {
+        assert_eq!(eval_source("3 < 5"), Value::Bool(true));
+        assert_eq!(eval_source("5 <= 4"), Value::Bool(false));
+    }
+
+    #[test]
+    fn closures_capture_their_defining_scope() {
+        let source = "var n = 10\nvar f = function(x) {\n  return x + n\n}\nf(5)";
+        assert_eq!(eval_source(source), Value::Int(15));
+    }
+
+    #[test]
+    fn return_exits_the_body_early() {
+        let source = "var f = function(x) {\n  return 1\n  return 2\n}\nf(0)";
+        assert_eq!(eval_source(source), Value::Int(1));
+    }
+
+    #[test]
+    fn compile_matches_interpret_for_arithmetic() {
+        let source = "var x = 5\nx + 2 * 3";
+        assert_eq!(compile_source(source), eval_source(source));
+    }
+
+    #[test]
+    fn compiling_a_trailing_declaration_yields_its_value() {
+        assert_eq!(compile_source("var y = 3"), Value::Int(3));
     }
 }